@@ -0,0 +1,77 @@
+// Copyright: Ankitects Pty Ltd and contributors
+// License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
+
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Json;
+use rusqlite::ErrorCode;
+use serde::Serialize;
+use thiserror::Error;
+
+/// Errors surfaced by the account-management HTTP handlers.
+///
+/// Unlike matching on `source().to_string()`, this lets us map well-known
+/// failure modes (e.g. a duplicate email) to the right status code without
+/// depending on the wording of the underlying SQLite error message.
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("an account with this email already exists")]
+    EmailExists,
+    #[error("invalid email address")]
+    EmailInvalid,
+    #[error("password must not be empty")]
+    EmptyPassword,
+    #[error("invalid email or password")]
+    InvalidCredentials,
+    #[error("too many failed attempts, try again later")]
+    TooManyAttempts,
+    #[error("internal error: {0}")]
+    Internal(Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    status: u16,
+    message: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ApiError::EmailExists | ApiError::EmailInvalid | ApiError::EmptyPassword => {
+                StatusCode::BAD_REQUEST
+            }
+            ApiError::InvalidCredentials => StatusCode::FORBIDDEN,
+            ApiError::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let body = ErrorBody {
+            status: status.as_u16(),
+            message: self.to_string(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> Self {
+        if let rusqlite::Error::SqliteFailure(ref sqlite_err, _) = err {
+            if sqlite_err.code == ErrorCode::ConstraintViolation
+                && sqlite_err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+            {
+                return ApiError::EmailExists;
+            }
+        }
+        ApiError::Internal(Box::new(err))
+    }
+}
+
+impl From<crate::sync::user::database::DbError> for ApiError {
+    fn from(err: crate::sync::user::database::DbError) -> Self {
+        match err {
+            crate::sync::user::database::DbError::Sqlite(e) => e.into(),
+            crate::sync::user::database::DbError::Pool(e) => ApiError::Internal(Box::new(e)),
+        }
+    }
+}
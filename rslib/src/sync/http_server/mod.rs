@@ -1,6 +1,7 @@
 // Copyright: Ankitects Pty Ltd and contributors
 // License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 
+mod api_error;
 mod handlers;
 mod logging;
 mod media_manager;
@@ -8,7 +9,6 @@ mod routes;
 mod user;
 
 use std::collections::HashMap;
-use std::error::Error;
 use std::future::Future;
 use std::future::IntoFuture;
 use std::net::IpAddr;
@@ -18,13 +18,18 @@ use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 
 use anki_io::create_dir_all;
 use axum::extract::DefaultBodyLimit;
+use clap::Parser;
+use clap::Subcommand;
 use axum::routing::get;
 use axum::routing::post;
 use axum::Router;
 use axum::{extract::State, http::StatusCode, Json};
+use axum_client_ip::SecureClientIp;
 use axum_client_ip::SecureClientIpSource;
 use snafu::ResultExt;
 use snafu::Whatever;
@@ -36,6 +41,7 @@ use crate::error;
 use crate::media::files::sha1_of_data;
 use crate::sync::error::HttpResult;
 use crate::sync::error::OrHttpErr;
+use crate::sync::http_server::api_error::ApiError;
 use crate::sync::http_server::logging::with_logging_layer;
 use crate::sync::http_server::media_manager::ServerMediaManager;
 use crate::sync::http_server::routes::collection_sync_router;
@@ -53,13 +59,61 @@ use crate::sync::user::database::{User as Account, UserDatabase};
 
 pub struct SimpleServer {
     state: Mutex<SimpleServerInner>,
+    /// The pool is its own lock; it must not be called while `state` is
+    /// held, or every request serializes on `state` exactly as if there
+    /// were no pool at all.
+    user_db: UserDatabase,
 }
 
 pub struct SimpleServerInner {
     base_folder: PathBuf,
     /// hkey->user
     users: HashMap<String, User>,
-    user_db: UserDatabase,
+    /// "ip:email" -> (failed attempt count, time of first attempt in the
+    /// current window)
+    login_attempts: HashMap<String, (u32, Instant)>,
+    /// "email" -> (failed attempt count, time of first attempt in the
+    /// current window), independent of source IP. Credential-stuffing
+    /// attackers rotate IPs specifically to dodge a per-IP-only limit, so
+    /// this tracks the account across all of them.
+    email_login_attempts: HashMap<String, (u32, Instant)>,
+}
+
+/// Failed `get_host_key`/`change_password` attempts allowed from a single IP
+/// against one account before lockout.
+const MAX_FAILED_LOGIN_ATTEMPTS: u32 = 5;
+/// Failed attempts allowed against one account across all IPs before
+/// lockout. Higher than the per-IP threshold since it also has to tolerate
+/// legitimate multi-IP traffic (e.g. a user switching networks).
+const MAX_FAILED_LOGIN_ATTEMPTS_PER_EMAIL: u32 = 20;
+/// Sliding window the failed-attempt counts above are measured over.
+const LOGIN_ATTEMPT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Whether `scope` has reached `max_attempts` failed attempts within the
+/// current [`LOGIN_ATTEMPT_WINDOW`].
+fn attempts_locked_out(
+    attempts: &mut HashMap<String, (u32, Instant)>,
+    scope: &str,
+    max_attempts: u32,
+) -> bool {
+    match attempts.get(scope) {
+        Some((count, first_attempt)) if first_attempt.elapsed() < LOGIN_ATTEMPT_WINDOW => {
+            *count >= max_attempts
+        }
+        Some(_) => {
+            attempts.remove(scope);
+            false
+        }
+        None => false,
+    }
+}
+
+fn record_attempt(attempts: &mut HashMap<String, (u32, Instant)>, scope: &str) {
+    let entry = attempts.entry(scope.to_string()).or_insert((0, Instant::now()));
+    if entry.1.elapsed() >= LOGIN_ATTEMPT_WINDOW {
+        *entry = (0, Instant::now());
+    }
+    entry.0 += 1;
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -92,39 +146,73 @@ pub fn default_ip_header() -> SecureClientIpSource {
     SecureClientIpSource::ConnectInfo
 }
 
-impl SimpleServerInner {
-    fn new_from_env(base_folder: &Path) -> error::Result<Self, Whatever> {
-        create_dir_all(base_folder).whatever_context("new_from_env")?;
-        let users: HashMap<String, User> = Default::default();
-        let user_db_path = base_folder.to_path_buf().join("user.db");
+#[derive(clap::Parser, Debug)]
+#[command(name = "anki-sync-server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-        let user_db = UserDatabase::new(&user_db_path.as_path()).whatever_context("new user db")?;
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage accounts in the server's user database without starting it.
+    Admin {
+        #[command(subcommand)]
+        action: AdminCommand,
+    },
+}
 
-        Ok(Self {
-            base_folder: base_folder.to_path_buf(),
-            users: users,
-            user_db: user_db,
-        })
+#[derive(Subcommand, Debug)]
+enum AdminCommand {
+    /// Create a new account.
+    CreateUser {
+        email: String,
+        password: String,
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Set a new password for an existing account and delete its persisted
+    /// sessions.
+    ///
+    /// This only updates the database; the admin CLI runs as a separate,
+    /// offline process. A sync server already running with the old host key
+    /// cached in memory keeps accepting it until restarted.
+    ResetPassword { email: String, new_password: String },
+    /// Delete an account and delete its persisted sessions.
+    ///
+    /// This only updates the database; the admin CLI runs as a separate,
+    /// offline process. A sync server already running with the old host key
+    /// cached in memory keeps accepting it until restarted.
+    DeleteUser { email: String },
+    /// List every account's email and display name.
+    ListUsers,
+}
+
+impl SimpleServerInner {
+    fn is_user_exists(&self, key: &str) -> bool {
+        self.users.contains_key(key)
     }
 
-    fn create_account(&self, account: &Account) -> error::Result<(), Whatever> {
-        self.user_db
-            .add_user(account)
-            .whatever_context("create account")
+    /// Whether `ip_email_scope` ("ip:email") or `email` alone has reached
+    /// its respective attempt threshold within the current
+    /// [`LOGIN_ATTEMPT_WINDOW`].
+    fn is_locked_out(&mut self, ip_email_scope: &str, email: &str) -> bool {
+        attempts_locked_out(&mut self.login_attempts, ip_email_scope, MAX_FAILED_LOGIN_ATTEMPTS)
+            || attempts_locked_out(
+                &mut self.email_login_attempts,
+                email,
+                MAX_FAILED_LOGIN_ATTEMPTS_PER_EMAIL,
+            )
     }
 
-    fn load_account_if(
-        &self,
-        name: &str,
-        password: &str,
-    ) -> error::Result<Option<Account>, Whatever> {
-        self.user_db
-            .verify_user(name, password)
-            .whatever_context("verify user")
+    fn record_failed_login(&mut self, ip_email_scope: &str, email: &str) {
+        record_attempt(&mut self.login_attempts, ip_email_scope);
+        record_attempt(&mut self.email_login_attempts, email);
     }
 
-    fn is_user_exists(&self, key: &str) -> bool {
-        self.users.contains_key(key)
+    fn clear_failed_logins(&mut self, ip_email_scope: &str, email: &str) {
+        self.login_attempts.remove(ip_email_scope);
+        self.email_login_attempts.remove(email);
     }
 
     fn create_user(&mut self, name: &str, hkey: &str) -> error::Result<(), Whatever> {
@@ -151,26 +239,17 @@ fn derive_hkey(user_and_pass: &str) -> String {
 async fn register_handler(
     State(server): State<Arc<SimpleServer>>,
     Json(payload): Json<RegisterRequest>,
-) -> (StatusCode, Json<RegisterResponse>) {
+) -> Result<(StatusCode, Json<RegisterResponse>), ApiError> {
     let email = payload.email.trim();
     let name = payload.name.trim();
     let password = payload.password.trim();
     if password == "" {
-        let response = RegisterResponse {
-            status: 400,
-            message: Some("empty_password".to_string()),
-        };
-        return (StatusCode::BAD_REQUEST, Json(response));
+        return Err(ApiError::EmptyPassword);
     }
     if !validate_email(email) {
-        let response = RegisterResponse {
-            status: 400,
-            message: Some("bad_email".to_string()),
-        };
-        return (StatusCode::BAD_REQUEST, Json(response));
+        return Err(ApiError::EmailInvalid);
     }
 
-    let state = server.state.lock().unwrap();
     let account = Account {
         id: 0,
         email: email.to_string(),
@@ -181,39 +260,138 @@ async fn register_handler(
         },
         password: Some(password.to_string()),
     };
-    let ret = state.create_account(&account);
+    server.create_account(&account)?;
 
-    match ret {
-        Ok(_) => {
-            let response = RegisterResponse {
-                status: 200,
-                message: Some("success".to_string()),
-            };
-            (StatusCode::OK, Json(response))
-        }
-        Err(e) => {
-            if let Some(source) = e.source() {
-                if source.to_string() == "UNIQUE constraint failed: user.email" {
-                    let response = RegisterResponse {
-                        status: 400,
-                        message: Some("account_exists".to_string()),
-                    };
-                    return (StatusCode::BAD_REQUEST, Json(response));
-                }
-            }
-            
-            let response = RegisterResponse {
-                status: 500,
-                message: Some(e.to_string()),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
-        }
+    let response = RegisterResponse {
+        status: 200,
+        message: Some("success".to_string()),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ChangePasswordRequest {
+    pub email: String,
+    pub old_password: String,
+    pub new_password: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ChangePasswordResponse {
+    pub status: u16,
+    pub message: Option<String>,
+}
+
+#[axum::debug_handler]
+async fn change_password_handler(
+    State(server): State<Arc<SimpleServer>>,
+    SecureClientIp(client_ip): SecureClientIp,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<(StatusCode, Json<ChangePasswordResponse>), ApiError> {
+    let email = payload.email.trim();
+    let new_password = payload.new_password.trim();
+    if new_password == "" {
+        return Err(ApiError::EmptyPassword);
     }
 
-    // 返回成功响应
+    let lockout_scope = format!("{}:{}", client_ip, email);
+    server.change_password(email, &payload.old_password, new_password, &lockout_scope)?;
+
+    let response = ChangePasswordResponse {
+        status: 200,
+        message: Some("success".to_string()),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[axum::debug_handler]
+async fn get_host_key_handler(
+    State(server): State<Arc<SimpleServer>>,
+    SecureClientIp(client_ip): SecureClientIp,
+    Json(request): Json<HostKeyRequest>,
+) -> HttpResult<SyncResponse<HostKeyResponse>> {
+    server.get_host_key(request, client_ip)
 }
 
 impl SimpleServer {
+    /// Creates an account. Does not touch `state`; the database call runs
+    /// without holding the in-memory-state lock.
+    fn create_account(&self, account: &Account) -> Result<(), ApiError> {
+        self.user_db.add_user(account).map_err(ApiError::from)
+    }
+
+    fn load_account_if(
+        &self,
+        name: &str,
+        password: &str,
+    ) -> error::Result<Option<Account>, Whatever> {
+        self.user_db
+            .verify_user(name, password)
+            .whatever_context("verify user")
+    }
+
+    fn is_locked_out(&self, ip_email_scope: &str, email: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .is_locked_out(ip_email_scope, email)
+    }
+
+    fn record_failed_login(&self, ip_email_scope: &str, email: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .record_failed_login(ip_email_scope, email)
+    }
+
+    fn clear_failed_logins(&self, ip_email_scope: &str, email: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .clear_failed_logins(ip_email_scope, email)
+    }
+
+    fn change_password(
+        &self,
+        email: &str,
+        old_password: &str,
+        new_password: &str,
+        lockout_scope: &str,
+    ) -> Result<(), ApiError> {
+        // Changing a password requires knowing the current one, so this
+        // endpoint is just as guessable as get_host_key and needs the same
+        // throttling.
+        if self.is_locked_out(lockout_scope, email) {
+            return Err(ApiError::TooManyAttempts);
+        }
+
+        let verified = self
+            .user_db
+            .verify_user(email, old_password)
+            .map_err(ApiError::from)?;
+        if verified.is_none() {
+            self.record_failed_login(lockout_scope, email);
+            return Err(ApiError::InvalidCredentials);
+        }
+        self.clear_failed_logins(lockout_scope, email);
+
+        self.user_db
+            .update_password(email, new_password)
+            .map_err(ApiError::from)?;
+        self.user_db
+            .delete_sessions_for_email(email)
+            .map_err(ApiError::from)?;
+
+        // Drop any host keys already cached in memory for this account, so
+        // the old password can no longer be used to sync.
+        self.state
+            .lock()
+            .unwrap()
+            .users
+            .retain(|_, user| user.name != email);
+        Ok(())
+    }
+
     pub(in crate::sync) async fn with_authenticated_user<F, I, O>(
         &self,
         req: SyncRequest<I>,
@@ -222,6 +400,21 @@ impl SimpleServer {
     where
         F: FnOnce(&mut User, SyncRequest<I>) -> HttpResult<O>,
     {
+        let already_cached = self.state.lock().unwrap().is_user_exists(&req.sync_key);
+        if !already_cached {
+            // Not in memory, e.g. after a restart; fall back to the
+            // persisted session before giving up. The database lookup runs
+            // outside the state lock.
+            let email = match self.user_db.get_session(&req.sync_key) {
+                Ok(email_opt) => email_opt.or_forbidden("invalid hkey")?,
+                Err(_) => None.or_internal_err("loading session")?,
+            };
+            let mut state = self.state.lock().unwrap();
+            if state.create_user(&email, &req.sync_key).is_err() {
+                return None.or_internal_err("restoring user from session");
+            }
+        }
+
         let mut state = self.state.lock().unwrap();
         let user = state
             .users
@@ -230,40 +423,56 @@ impl SimpleServer {
         Span::current().record("uid", &user.name);
         Span::current().record("client", &req.client_version);
         Span::current().record("session", &req.session_key);
-        println!("111111111111");
         op(user, req)
     }
 
     pub(in crate::sync) fn get_host_key(
         &self,
         request: HostKeyRequest,
+        client_ip: IpAddr,
     ) -> HttpResult<SyncResponse<HostKeyResponse>> {
-        let mut state = self.state.lock().unwrap();
+        let lockout_scope = format!("{}:{}", client_ip, request.username);
+        if self.is_locked_out(&lockout_scope, &request.username) {
+            return None.or_forbidden("too many failed login attempts, try again later");
+        }
+
+        // The credential check and session persistence below run without
+        // holding the state lock, so a slow database doesn't serialize
+        // unrelated requests.
+        let result = self.load_account_if(&request.username, &request.password);
+        let opt_user = match result {
+            Ok(opt_user) => opt_user,
+            Err(_) => {
+                self.record_failed_login(&lockout_scope, &request.username);
+                return None.or_internal_err("load user fail");
+            }
+        };
+        if opt_user.is_none() {
+            self.record_failed_login(&lockout_scope, &request.username);
+            return None.or_forbidden("invalid user/pass in get_host_key");
+        }
+        self.clear_failed_logins(&lockout_scope, &request.username);
 
-        let result = state.load_account_if(&request.username, &request.password);
-        match result {
-            Ok(opt_user) => {
-                if let Some(_) = opt_user {
-                    let name = &request.username;
-                    let password = &request.password;
-                    let val = format!("{}:{}", name, password);
-                    let key = derive_hkey(&val);
-                    if !state.is_user_exists(&key) {
-                        let ret = state.create_user(name, &key);
-                        match ret {
-                            Ok(_) => SyncResponse::try_from_obj(HostKeyResponse { key }),
-                            Err(_) => None.or_internal_err("create user fail"),
-                        }
-                    } else {
-                        SyncResponse::try_from_obj(HostKeyResponse { key })
-                    }
-                } else {
-                    None.or_forbidden("invalid user/pass in get_host_key")
+        let name = &request.username;
+        let password = &request.password;
+        let val = format!("{}:{}", name, password);
+        let key = derive_hkey(&val);
+
+        let already_cached = self.state.lock().unwrap().is_user_exists(&key);
+        if !already_cached {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.create_user(name, &key).is_err() {
+                    return None.or_internal_err("create user fail");
                 }
             }
-            Err(_) => None.or_internal_err("load user fail"),
+            if self.user_db.add_session(&key, name).is_err() {
+                return None.or_internal_err("create user fail");
+            }
         }
+        SyncResponse::try_from_obj(HostKeyResponse { key })
     }
+
     pub fn is_running() -> bool {
         let config = envy::prefixed("SYNC_")
             .from_env::<SyncServerConfig>()
@@ -271,9 +480,23 @@ impl SimpleServer {
         std::net::TcpStream::connect(format!("{}:{}", config.host, config.port)).is_ok()
     }
     pub fn new(base_folder: &Path) -> error::Result<Self, Whatever> {
-        let inner = SimpleServerInner::new_from_env(base_folder)?;
+        create_dir_all(base_folder).whatever_context("creating SYNC_BASE")?;
+        let user_db_path = base_folder.to_path_buf().join("user.db");
+        let user_db = UserDatabase::new(&user_db_path).whatever_context("new user db")?;
+
+        // Persisted sessions are not preloaded here; `with_authenticated_user`
+        // restores them lazily from the database on first use, so eagerly
+        // walking every row at startup would just pay the same cost sooner.
+        let inner = SimpleServerInner {
+            base_folder: base_folder.to_path_buf(),
+            users: Default::default(),
+            login_attempts: Default::default(),
+            email_login_attempts: Default::default(),
+        };
+
         Ok(SimpleServer {
             state: Mutex::new(inner),
+            user_db,
         })
     }
 
@@ -294,6 +517,8 @@ impl SimpleServer {
                 .nest("/msync", media_sync_router())
                 .route("/health", get(health_check_handler))
                 .route("/register", post(register_handler))
+                .route("/change-password", post(change_password_handler))
+                .route("/hostkey", post(get_host_key_handler))
                 .with_state(server)
                 .layer(DefaultBodyLimit::max(*MAXIMUM_SYNC_PAYLOAD_BYTES))
                 .layer(config.ip_header.into_extension()),
@@ -313,14 +538,162 @@ impl SimpleServer {
     #[snafu::report]
     #[tokio::main]
     pub async fn run() -> error::Result<(), Whatever> {
+        let cli = Cli::parse();
         let config = envy::prefixed("SYNC_")
             .from_env::<SyncServerConfig>()
             .whatever_context("reading SYNC_* env vars")?;
+
+        if let Some(Command::Admin { action }) = cli.command {
+            return Self::run_admin(&config.base_folder, action);
+        }
+
         println!("{:#?}", config);
         let (_addr, server_fut) = SimpleServer::make_server(config).await?;
         server_fut.await.whatever_context("await server")?;
         Ok(())
     }
+
+    fn run_admin(base_folder: &Path, action: AdminCommand) -> error::Result<(), Whatever> {
+        create_dir_all(base_folder).whatever_context("admin: preparing base folder")?;
+        let user_db = UserDatabase::new(&base_folder.join("user.db"))
+            .whatever_context("admin: opening user db")?;
+
+        match action {
+            AdminCommand::CreateUser {
+                email,
+                password,
+                name,
+            } => {
+                user_db
+                    .add_user(&Account {
+                        id: 0,
+                        email,
+                        name,
+                        password: Some(password),
+                    })
+                    .whatever_context("admin: create-user")?;
+                println!("user created");
+            }
+            AdminCommand::ResetPassword {
+                email,
+                new_password,
+            } => {
+                let updated = user_db
+                    .update_password(&email, &new_password)
+                    .whatever_context("admin: reset-password")?;
+                if !updated {
+                    snafu::whatever!("no such user: {email}");
+                }
+                user_db
+                    .delete_sessions_for_email(&email)
+                    .whatever_context("admin: invalidating sessions")?;
+                println!("password reset");
+            }
+            AdminCommand::DeleteUser { email } => {
+                let deleted = user_db
+                    .delete_user(&email)
+                    .whatever_context("admin: delete-user")?;
+                if !deleted {
+                    snafu::whatever!("no such user: {email}");
+                }
+                user_db
+                    .delete_sessions_for_email(&email)
+                    .whatever_context("admin: invalidating sessions")?;
+                println!("user deleted");
+            }
+            AdminCommand::ListUsers => {
+                let users = user_db
+                    .list_users()
+                    .whatever_context("admin: list-users")?;
+                for user in users {
+                    println!("{}\t{}", user.email, user.name.unwrap_or_default());
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 pub type ServerFuture = Pin<Box<dyn Future<Output = error::Result<(), std::io::Error>> + Send>>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn inner() -> SimpleServerInner {
+        SimpleServerInner {
+            base_folder: PathBuf::new(),
+            users: Default::default(),
+            login_attempts: Default::default(),
+            email_login_attempts: Default::default(),
+        }
+    }
+
+    #[test]
+    fn lockout_triggers_after_max_attempts_and_resets_on_success() {
+        let mut state = inner();
+        let scope = "1.2.3.4:user@example.com";
+        let email = "user@example.com";
+
+        for _ in 0..MAX_FAILED_LOGIN_ATTEMPTS - 1 {
+            state.record_failed_login(scope, email);
+            assert!(!state.is_locked_out(scope, email));
+        }
+
+        state.record_failed_login(scope, email);
+        assert!(state.is_locked_out(scope, email));
+
+        state.clear_failed_logins(scope, email);
+        assert!(!state.is_locked_out(scope, email));
+    }
+
+    #[test]
+    fn lockout_expires_after_window_elapses() {
+        let mut state = inner();
+        let scope = "1.2.3.4:user@example.com";
+        let email = "user@example.com";
+
+        for _ in 0..MAX_FAILED_LOGIN_ATTEMPTS {
+            state.record_failed_login(scope, email);
+        }
+        assert!(state.is_locked_out(scope, email));
+
+        // Backdate the attempt window's start so it looks expired.
+        let entry = state.login_attempts.get_mut(scope).unwrap();
+        entry.1 = entry.1.checked_sub(LOGIN_ATTEMPT_WINDOW).unwrap();
+        let entry = state.email_login_attempts.get_mut(email).unwrap();
+        entry.1 = entry.1.checked_sub(LOGIN_ATTEMPT_WINDOW).unwrap();
+
+        assert!(!state.is_locked_out(scope, email));
+    }
+
+    #[test]
+    fn lockout_scopes_are_independent() {
+        let mut state = inner();
+
+        for _ in 0..MAX_FAILED_LOGIN_ATTEMPTS {
+            state.record_failed_login("1.2.3.4:user@example.com", "user@example.com");
+        }
+
+        assert!(state.is_locked_out("1.2.3.4:user@example.com", "user@example.com"));
+        assert!(!state.is_locked_out("5.6.7.8:user@example.com", "user@example.com"));
+        assert!(!state.is_locked_out("1.2.3.4:other@example.com", "other@example.com"));
+    }
+
+    #[test]
+    fn per_email_lockout_blocks_attacker_rotating_ips() {
+        let mut state = inner();
+        let email = "user@example.com";
+
+        // Every attempt comes from a different IP, so the per-IP counter
+        // never trips, but the per-email counter should still catch it.
+        for i in 0..MAX_FAILED_LOGIN_ATTEMPTS_PER_EMAIL {
+            let scope = format!("10.0.0.{}:{}", i, email);
+            assert!(!state.is_locked_out(&scope, email));
+            state.record_failed_login(&scope, email);
+        }
+
+        let final_scope = format!("10.0.0.{}:{}", MAX_FAILED_LOGIN_ATTEMPTS_PER_EMAIL, email);
+        assert!(state.is_locked_out(&final_scope, email));
+    }
+}
@@ -1,16 +1,61 @@
 use std::path::Path;
+use std::time::Duration;
 
 use crate::error;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::params;
-use rusqlite::{Connection, Result, Row};
+use rusqlite::Row;
 
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::PasswordHash;
+use argon2::password_hash::PasswordHasher;
+use argon2::password_hash::PasswordVerifier;
+use argon2::password_hash::SaltString;
+use argon2::Argon2;
 use md5::compute;
 
+#[derive(Debug, thiserror::Error)]
+pub enum DbError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Pool(#[from] r2d2::Error),
+}
+
+pub type Result<T> = std::result::Result<T, DbError>;
+
+/// Legacy password hashes are bare 32-char hex MD5 digests with no `$`
+/// separators; Argon2 PHC strings always start with `$argon2`.
+fn is_legacy_md5_hash(hash: &str) -> bool {
+    hash.len() == 32 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
 fn calculate_md5(password: &str) -> String {
     let result = compute(password);
     format!("{:x}", result)
 }
 
+fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("hashing should not fail")
+        .to_string()
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    if is_legacy_md5_hash(hash) {
+        return calculate_md5(password) == hash;
+    }
+    let Ok(parsed_hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok()
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct User {
     pub id: u64,
@@ -19,6 +64,22 @@ pub struct User {
     pub password: Option<String>,
 }
 
+/// A persisted host key, surviving server restarts.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Session {
+    pub hkey: String,
+    pub email: String,
+}
+
+impl Session {
+    fn from_row(row: &Row) -> error::Result<Self, rusqlite::Error> {
+        Ok(Self {
+            hkey: row.get(0)?,
+            email: row.get(1)?,
+        })
+    }
+}
+
 impl User {
     fn from_row(row: &Row) -> error::Result<Self, rusqlite::Error> {
         Ok(Self {
@@ -31,48 +92,152 @@ impl User {
 }
 
 pub struct UserDatabase {
-    db: Connection,
+    pool: Pool<SqliteConnectionManager>,
 }
 
 impl UserDatabase {
     pub fn new(path: &Path) -> Result<Self> {
         Ok(Self {
-            db: open_or_create_db(path)?,
+            pool: open_or_create_db(path)?,
         })
     }
 
     pub fn verify_user(&self, email: &str, password: &str) -> Result<Option<User>> {
-        let hashed_password = calculate_md5(password);
-        self.db
-            .prepare_cached(include_str!("verify_user.sql"))?
-            .query_row(params![email, hashed_password], User::from_row)
+        let db = self.pool.get()?;
+        let stored = match db
+            .prepare_cached(include_str!("get_password.sql"))?
+            .query_row(params![email], |row| row.get::<_, Option<String>>(0))
+        {
+            // No password set (e.g. an account created without one) is
+            // treated the same as no matching row.
+            Ok(None) => return Ok(None),
+            Ok(Some(hash)) => hash,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        if !verify_password(password, &stored) {
+            return Ok(None);
+        }
+
+        if is_legacy_md5_hash(&stored) {
+            let upgraded = hash_password(password);
+            db.prepare_cached(include_str!("update_password.sql"))?
+                .execute(params![upgraded, email])?;
+        }
+
+        db.prepare_cached(include_str!("verify_user.sql"))?
+            .query_row(params![email], User::from_row)
             .map(|user| Some(user))
             .or_else(|err| match err {
                 rusqlite::Error::QueryReturnedNoRows => Ok(None),
                 e => Err(e),
             })
+            .map_err(DbError::from)
     }
 
     pub fn add_user(&self, user: &User) -> Result<()> {
-        let mut hashed_password: Option<String> = None;
-        if let Some(ref password) = user.password {
-            hashed_password = Some(calculate_md5(password));
-        }
-        self
-            .db
+        let hashed_password = user.password.as_deref().map(hash_password);
+        self.pool
+            .get()?
             .prepare_cached(include_str!("add_user.sql"))?
             .execute(params![user.email, user.name, hashed_password])?;
         Ok(())
     }
+
+    /// Persists a host key so it survives a server restart.
+    pub fn add_session(&self, hkey: &str, email: &str) -> Result<()> {
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        self.pool
+            .get()?
+            .prepare_cached(include_str!("add_session.sql"))?
+            .execute(params![hkey, email, created_at])?;
+        Ok(())
+    }
+
+    /// Reloads every persisted session, e.g. to repopulate an in-memory
+    /// cache after a restart.
+    pub fn all_sessions(&self) -> Result<Vec<Session>> {
+        let sessions = self
+            .pool
+            .get()?
+            .prepare_cached(include_str!("all_sessions.sql"))?
+            .query_map([], Session::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(sessions)
+    }
+
+    /// Looks up the email a host key was issued for, e.g. when the key is
+    /// missing from an in-memory cache after a crash.
+    pub fn get_session(&self, hkey: &str) -> Result<Option<String>> {
+        self.pool
+            .get()?
+            .prepare_cached(include_str!("get_session.sql"))?
+            .query_row(params![hkey], |row| row.get::<_, String>(0))
+            .map(Some)
+            .or_else(|err| match err {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                e => Err(e),
+            })
+            .map_err(DbError::from)
+    }
+
+    /// Re-hashes and stores a new password for an existing account. Returns
+    /// `false` if no account has this email.
+    pub fn update_password(&self, email: &str, new_password: &str) -> Result<bool> {
+        let hashed_password = hash_password(new_password);
+        let affected = self
+            .pool
+            .get()?
+            .prepare_cached(include_str!("update_password.sql"))?
+            .execute(params![hashed_password, email])?;
+        Ok(affected > 0)
+    }
+
+    /// Deletes an account. Returns `false` if no account has this email.
+    pub fn delete_user(&self, email: &str) -> Result<bool> {
+        let affected = self
+            .pool
+            .get()?
+            .prepare_cached(include_str!("delete_user.sql"))?
+            .execute(params![email])?;
+        Ok(affected > 0)
+    }
+
+    pub fn list_users(&self) -> Result<Vec<User>> {
+        let users = self
+            .pool
+            .get()?
+            .prepare_cached(include_str!("list_users.sql"))?
+            .query_map([], User::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(users)
+    }
+
+    /// Deletes every persisted host key issued to an account, e.g. after its
+    /// password changes or the account is deleted. Host keys a running
+    /// server already holds in memory are not affected.
+    pub fn delete_sessions_for_email(&self, email: &str) -> Result<()> {
+        self.pool
+            .get()?
+            .prepare_cached(include_str!("delete_sessions_for_email.sql"))?
+            .execute(params![email])?;
+        Ok(())
+    }
 }
 
-fn open_or_create_db(path: &Path) -> Result<Connection> {
-    let db = Connection::open(path)?;
-    db.busy_timeout(std::time::Duration::from_secs(0))?;
-    db.pragma_update(None, "locking_mode", "exclusive")?;
-    db.pragma_update(None, "journal_mode", "wal")?;
-    db.execute_batch(include_str!("schema.sql"))?;
-    Ok(db)
+fn open_or_create_db(path: &Path) -> Result<Pool<SqliteConnectionManager>> {
+    let manager = SqliteConnectionManager::file(path).with_init(|db| {
+        db.busy_timeout(Duration::from_secs(5))?;
+        db.pragma_update(None, "journal_mode", "wal")?;
+        db.execute_batch(include_str!("schema.sql"))?;
+        Ok(())
+    });
+    let pool = Pool::new(manager)?;
+    Ok(pool)
 }
 
 #[cfg(test)]
@@ -115,4 +280,98 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn legacy_md5_hash_is_upgraded_on_login() -> Result<(), String> {
+        let dir = PathBuf::from("./tmp/db_md5");
+        create_dir_all(dir.as_path()).map_err(error_to_string)?;
+        let user_db = dir.join("user.db");
+        let found = exists(user_db.as_path()).map_err(error_to_string)?;
+        if found {
+            remove_file(user_db.as_path()).map_err(error_to_string)?;
+        }
+
+        let db = UserDatabase::new(user_db.as_path()).map_err(error_to_string)?;
+        let email = "legacy@gmai.com";
+        let password = "hunter2";
+
+        // Insert a legacy bare-MD5 hash directly, bypassing `add_user`'s
+        // Argon2 hashing, to simulate an account created before the switch.
+        let legacy_hash = super::calculate_md5(password);
+        db.pool
+            .get()
+            .map_err(error_to_string)?
+            .execute(
+                "insert into user (email, name, password) values (?1, ?2, ?3)",
+                rusqlite::params![email, Option::<String>::None, legacy_hash],
+            )
+            .map_err(error_to_string)?;
+
+        let stored_before = db
+            .pool
+            .get()
+            .map_err(error_to_string)?
+            .query_row(
+                "select password from user where email = ?1",
+                rusqlite::params![email],
+                |row| row.get::<_, String>(0),
+            )
+            .map_err(error_to_string)?;
+        assert!(super::is_legacy_md5_hash(&stored_before));
+
+        let verified = db.verify_user(email, password).map_err(error_to_string)?;
+        assert!(verified.is_some());
+
+        let stored_after = db
+            .pool
+            .get()
+            .map_err(error_to_string)?
+            .query_row(
+                "select password from user where email = ?1",
+                rusqlite::params![email],
+                |row| row.get::<_, String>(0),
+            )
+            .map_err(error_to_string)?;
+        assert!(!super::is_legacy_md5_hash(&stored_after));
+        assert!(super::verify_password(password, &stored_after));
+
+        Ok(())
+    }
+
+    #[test]
+    fn persisted_session_survives_a_fresh_connection() -> Result<(), String> {
+        let dir = PathBuf::from("./tmp/db_sessions");
+        create_dir_all(dir.as_path()).map_err(error_to_string)?;
+        let user_db = dir.join("user.db");
+        let found = exists(user_db.as_path()).map_err(error_to_string)?;
+        if found {
+            remove_file(user_db.as_path()).map_err(error_to_string)?;
+        }
+
+        let email = "sessions@gmai.com";
+        let hkey = "some-host-key";
+
+        {
+            let db = UserDatabase::new(user_db.as_path()).map_err(error_to_string)?;
+            db.add_session(hkey, email).map_err(error_to_string)?;
+        }
+
+        // A fresh `UserDatabase` (as a restarted server would create) should
+        // still see the session, since it's persisted on disk rather than
+        // held in memory.
+        let db = UserDatabase::new(user_db.as_path()).map_err(error_to_string)?;
+
+        let found_email = db.get_session(hkey).map_err(error_to_string)?;
+        assert_eq!(found_email, Some(email.to_string()));
+
+        let missing = db.get_session("no-such-key").map_err(error_to_string)?;
+        assert_eq!(missing, None);
+
+        let sessions = db.all_sessions().map_err(error_to_string)?;
+        assert!(sessions
+            .iter()
+            .any(|session| session.hkey == hkey && session.email == email));
+
+        Ok(())
+    }
 }